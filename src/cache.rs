@@ -0,0 +1,84 @@
+//! Bounded cache of `/query.json` results keyed by the exact SQL text.
+//!
+//! The dataset only changes every [`crate::TIME_TO_REFRESH`], so repeated
+//! identical queries between refreshes can be served without touching
+//! SQLite at all. [`QueryCache::clear`] is called right after a refresh
+//! commits so stale rows are never served from a pre-refresh entry.
+//!
+//! A plain `clear()` isn't quite enough on its own: a reader can read V1
+//! from SQLite, have a refresh land and `clear()` run while its query is
+//! still in flight, and then insert V1 into the now-empty cache, where it
+//! would sit until the *next* refresh. [`QueryCache::generation`] /
+//! [`QueryCache::insert_if_current`] close that window — callers capture
+//! the generation before reading and the insert is dropped if a refresh
+//! bumped it in the meantime.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use lru::LruCache;
+use serde_json::Value;
+
+pub struct QueryCache {
+    inner: RwLock<LruCache<String, Arc<Value>>>,
+    generation: AtomicU64,
+}
+
+const DEFAULT_CACHE_SIZE: usize = 256;
+
+impl QueryCache {
+    pub fn from_env() -> Self {
+        let size = std::env::var("QUERY_CACHE_SIZE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .and_then(NonZeroUsize::new)
+            .unwrap_or(NonZeroUsize::new(DEFAULT_CACHE_SIZE).unwrap());
+        Self {
+            inner: RwLock::new(LruCache::new(size)),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Uses `peek` rather than `get` so a cache lookup only needs a read
+    /// lock instead of promoting the entry, which would require exclusive
+    /// access to the underlying list.
+    pub fn get(&self, sql: &str) -> Option<Arc<Value>> {
+        self.inner
+            .read()
+            .expect("query cache poisoned")
+            .peek(sql)
+            .cloned()
+    }
+
+    /// Current refresh generation. Callers should capture this before
+    /// running the query whose result they intend to cache, then pass it
+    /// back to [`Self::insert_if_current`].
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Inserts `value` only if no refresh has happened since `expected`
+    /// was captured, so a read that straddles a refresh can't seed the
+    /// cache with a result that's already stale. The generation check and
+    /// the insert happen under the same write lock that `clear` uses, so
+    /// a `clear` racing with this call can't land between the check and
+    /// the write.
+    pub fn insert_if_current(&self, sql: String, value: Arc<Value>, expected: u64) {
+        let mut inner = self.inner.write().expect("query cache poisoned");
+        if self.generation.load(Ordering::Acquire) != expected {
+            return;
+        }
+        inner.put(sql, value);
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.write().expect("query cache poisoned");
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        inner.clear();
+    }
+}
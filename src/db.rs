@@ -0,0 +1,65 @@
+//! Shared read-only access to `db.sqlite`, used by both the raw-SQL
+//! endpoint and the GraphQL schema.
+
+use std::{cell::RefCell, time::Duration};
+
+use rusqlite::{types::FromSql, OpenFlags};
+
+/// URI used to open per-worker read connections. `cache=shared` lets every
+/// connection opened with this URI in the process share one page cache,
+/// so concurrent readers don't each pay for their own copy of hot pages.
+const READ_DB_URI: &str = "file:db.sqlite?cache=shared";
+
+/// How long a reader retries against `SQLITE_BUSY` before giving up. The
+/// background refresh (see `spawn_refresh_loop` in `main.rs`) holds a
+/// write lock for the length of its `DELETE`+`INSERT` transaction; without
+/// this, a read landing in that window fails immediately instead of
+/// waiting the commit out.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+thread_local! {
+    /// One read-only connection per worker thread, opened lazily on first
+    /// use and kept open for the thread's lifetime instead of per request.
+    static READ_CONN: RefCell<Option<rusqlite::Connection>> = RefCell::new(None);
+}
+
+pub fn with_read_conn<R, E: From<rusqlite::Error>>(
+    f: impl FnOnce(&rusqlite::Connection) -> Result<R, E>,
+) -> Result<R, E> {
+    READ_CONN.with(|cell| {
+        let mut slot = cell.borrow_mut();
+        if slot.is_none() {
+            let conn = rusqlite::Connection::open_with_flags(
+                READ_DB_URI,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+            )?;
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            *slot = Some(conn);
+        }
+        f(slot.as_ref().expect("just initialized"))
+    })
+}
+
+pub struct DbValue(pub serde_json::Value);
+
+impl FromSql for DbValue {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        use rusqlite::types::ValueRef;
+        Ok(Self(match value {
+            ValueRef::Blob(bs) => serde_json::Value::String({
+                let mut buf = String::new();
+                for b in bs {
+                    use std::fmt::Write;
+                    write!(buf, "{:02x}", b).expect("Hex formatting cannot fail");
+                }
+                buf
+            }),
+            ValueRef::Real(f) => serde_json::Number::from_f64(f)
+                .map(serde_json::Value::Number)
+                .unwrap_or_else(|| serde_json::Value::String(format!("{}", f))),
+            ValueRef::Integer(i) => serde_json::Value::Number(serde_json::Number::from(i)),
+            ValueRef::Text(s) => serde_json::Value::String(String::from_utf8_lossy(s).to_string()),
+            ValueRef::Null => serde_json::Value::Null,
+        }))
+    }
+}
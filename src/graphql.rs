@@ -0,0 +1,223 @@
+//! Typed GraphQL alternative to the raw `/query.json` endpoint, covering
+//! the `versions` and `versionTypes` tables. Reuses the same read-only
+//! connection pool as `perform_query` (see [`crate::db`]), just with
+//! typed row mapping instead of [`crate::db::DbValue`]'s generic JSON.
+//!
+//! Unlike `/query.json` this endpoint isn't behind the rate limiter or
+//! the query semaphore/deadline, since a GraphQL document can't run
+//! arbitrary SQL. Instead it's bounded by [`build_schema`]'s depth and
+//! complexity limits, and the root `versions`/`versionTypes` queries by the
+//! default/max page size in [`paginated_query`]. The nested
+//! `VersionType.versions` field is not paginated: it returns every version
+//! of its type.
+
+use actix_web::HttpResponse;
+use async_graphql::{
+    http::GraphiQLSource, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject, ID,
+};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use rusqlite::{params_from_iter, types::Value as SqlValue, OptionalExtension};
+
+use crate::db::with_read_conn;
+
+#[derive(Clone, SimpleObject)]
+#[graphql(complex)]
+struct Version {
+    id: ID,
+    game_version_type_id: ID,
+    name: String,
+    slug: String,
+}
+
+#[async_graphql::ComplexObject]
+impl Version {
+    async fn version_type(&self) -> async_graphql::Result<Option<VersionType>> {
+        let id: i64 = self.game_version_type_id.parse()?;
+        let found = with_read_conn(|conn| {
+            conn.query_row(
+                "SELECT id, name, slug FROM versionTypes WHERE id = ?1",
+                [id],
+                |row| {
+                    Ok(VersionType {
+                        id: ID(row.get::<_, i64>(0)?.to_string()),
+                        name: row.get(1)?,
+                        slug: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+        })?;
+        Ok(found)
+    }
+}
+
+#[derive(Clone, SimpleObject)]
+#[graphql(complex)]
+struct VersionType {
+    id: ID,
+    name: String,
+    slug: String,
+}
+
+#[async_graphql::ComplexObject]
+impl VersionType {
+    async fn versions(&self) -> async_graphql::Result<Vec<Version>> {
+        let id: i64 = self.id.parse()?;
+        let versions = with_read_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, gameVersionTypeID, name, slug FROM versions \
+                 WHERE gameVersionTypeID = ?1 ORDER BY id",
+            )?;
+            stmt.query_map([id], row_to_version)?.collect()
+        })?;
+        Ok(versions)
+    }
+}
+
+fn row_to_version(row: &rusqlite::Row<'_>) -> rusqlite::Result<Version> {
+    Ok(Version {
+        id: ID(row.get::<_, i64>(0)?.to_string()),
+        game_version_type_id: ID(row.get::<_, i64>(1)?.to_string()),
+        name: row.get(2)?,
+        slug: row.get(3)?,
+    })
+}
+
+fn row_to_version_type(row: &rusqlite::Row<'_>) -> rusqlite::Result<VersionType> {
+    Ok(VersionType {
+        id: ID(row.get::<_, i64>(0)?.to_string()),
+        name: row.get(1)?,
+        slug: row.get(2)?,
+    })
+}
+
+/// Escapes `%` and `_` so a `name` filter matches only as a literal
+/// substring, not as a LIKE pattern; paired with `ESCAPE '\'` below.
+fn escape_like_pattern(raw: &str) -> String {
+    raw.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Page size used when a root query omits `first`, so an unbounded
+/// `{ versions { ... } }` can't dump the whole table in one response.
+const DEFAULT_PAGE_SIZE: i32 = 100;
+/// Upper bound on `first` regardless of what the caller asks for.
+const MAX_PAGE_SIZE: i32 = 500;
+
+/// Builds `WHERE 1 = 1 [AND slug = ?] [AND name LIKE ?] [AND id > ?] ORDER BY id LIMIT ?`
+/// from the optional pagination/filter arguments shared by both root
+/// queries, so the two resolvers don't duplicate the same string-building.
+fn paginated_query(
+    base_sql: &str,
+    slug: Option<String>,
+    name: Option<String>,
+    first: Option<i32>,
+    after: Option<String>,
+) -> rusqlite::Result<(String, Vec<SqlValue>)> {
+    let mut sql = format!("{base_sql} WHERE 1 = 1");
+    let mut params = Vec::new();
+
+    if let Some(slug) = slug {
+        sql.push_str(" AND slug = ?");
+        params.push(SqlValue::Text(slug));
+    }
+    if let Some(name) = name {
+        sql.push_str(" AND name LIKE ? ESCAPE '\\'");
+        params.push(SqlValue::Text(format!("%{}%", escape_like_pattern(&name))));
+    }
+    if let Some(after) = after {
+        let after_id: i64 = after
+            .parse()
+            .map_err(|_| rusqlite::Error::InvalidParameterName(after))?;
+        sql.push_str(" AND id > ?");
+        params.push(SqlValue::Integer(after_id));
+    }
+    sql.push_str(" ORDER BY id");
+    {
+        let first = first.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        sql.push_str(" LIMIT ?");
+        params.push(SqlValue::Integer(first as i64));
+    }
+
+    Ok((sql, params))
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn versions(
+        &self,
+        slug: Option<String>,
+        name: Option<String>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Vec<Version>> {
+        let versions = with_read_conn(|conn| {
+            let (sql, params) = paginated_query(
+                "SELECT id, gameVersionTypeID, name, slug FROM versions",
+                slug,
+                name,
+                first,
+                after,
+            )?;
+            let mut stmt = conn.prepare(&sql)?;
+            stmt.query_map(params_from_iter(params), row_to_version)?
+                .collect()
+        })?;
+        Ok(versions)
+    }
+
+    async fn version_types(
+        &self,
+        slug: Option<String>,
+        name: Option<String>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Vec<VersionType>> {
+        let version_types = with_read_conn(|conn| {
+            let (sql, params) = paginated_query(
+                "SELECT id, name, slug FROM versionTypes",
+                slug,
+                name,
+                first,
+                after,
+            )?;
+            let mut stmt = conn.prepare(&sql)?;
+            stmt.query_map(params_from_iter(params), row_to_version_type)?
+                .collect()
+        })?;
+        Ok(version_types)
+    }
+}
+
+pub type CursemapSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Depth limit on a query document, so a client can't nest
+/// `versionType { versions { versionType { ... } } }` arbitrarily deep to
+/// multiply the N+1 lookups each level performs.
+const MAX_QUERY_DEPTH: usize = 6;
+/// Complexity budget per request, on top of the depth limit and the
+/// root queries' page size cap in [`paginated_query`].
+const MAX_QUERY_COMPLEXITY: usize = 200;
+
+pub fn build_schema() -> CursemapSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish()
+}
+
+pub async fn graphql_handler(
+    schema: actix_web::web::Data<CursemapSchema>,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    schema.execute(req.into_inner()).await.into()
+}
+
+pub async fn graphql_playground() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(GraphiQLSource::build().endpoint("/graphql").finish())
+}
@@ -4,16 +4,30 @@ use core::fmt;
 use http::StatusCode;
 use serde;
 use std::{
-    sync::Arc,
-    time::{Duration, Instant},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::Mutex;
+use tokio::sync::Semaphore;
 
 use actix_web::{
     dev::{fn_service, ServiceRequest, ServiceResponse},
-    web, App, Error, HttpServer, ResponseError,
+    web, App, Error, HttpResponse, HttpServer, ResponseError,
 };
-use rusqlite::{self, types::FromSql, OpenFlags, Params};
+use rusqlite::{self, OpenFlags, Params};
+
+mod cache;
+mod db;
+mod graphql;
+mod metrics;
+mod ratelimit;
+
+use cache::QueryCache;
+use db::{with_read_conn, DbValue};
+use metrics::Metrics;
+use ratelimit::{RateLimiter, RateLimiterConfig};
 
 struct WrappedError<T>(T, actix_web::http::StatusCode);
 
@@ -25,6 +39,10 @@ impl<T> WrappedError<T> {
     fn internal(err: T) -> Self {
         Self(err, actix_web::http::StatusCode::INTERNAL_SERVER_ERROR)
     }
+
+    fn unavailable(err: T) -> Self {
+        Self(err, actix_web::http::StatusCode::SERVICE_UNAVAILABLE)
+    }
 }
 
 impl<T: fmt::Display> fmt::Display for WrappedError<T> {
@@ -45,84 +63,160 @@ impl<T: fmt::Display + fmt::Debug> ResponseError for WrappedError<T> {
     }
 }
 
-struct DbValue(serde_json::Value);
-
-impl FromSql for DbValue {
-    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
-        use rusqlite::types::ValueRef;
-        Ok(Self(match value {
-            ValueRef::Blob(bs) => serde_json::Value::String({
-                let mut buf = String::new();
-                for b in bs {
-                    use std::fmt::Write;
-                    write!(buf, "{:02x}", b).expect("Hex formatting cannot fail");
-                }
-                buf
-            }),
-            ValueRef::Real(f) => serde_json::Number::from_f64(f)
-                .map(serde_json::Value::Number)
-                .unwrap_or_else(|| serde_json::Value::String(format!("{}", f))),
-            ValueRef::Integer(i) => serde_json::Value::Number(serde_json::Number::from(i)),
-            ValueRef::Text(s) => serde_json::Value::String(String::from_utf8_lossy(s).to_string()),
-            ValueRef::Null => serde_json::Value::Null,
-        }))
+/// Maximum number of `/query.json` statements allowed to run against
+/// SQLite at once. Bounds how much blocked query work a burst of requests
+/// can pile onto the executor.
+const MAX_CONCURRENT_QUERIES: usize = 32;
+
+/// Per-query deadline and row cap, read once from the environment at
+/// startup. Protects the open `/query.json` endpoint from statements that
+/// would otherwise hang a worker (cartesian joins, `WITH RECURSIVE` loops)
+/// or exhaust memory collecting an enormous result set.
+#[derive(Clone, Copy)]
+struct QueryLimits {
+    timeout: Duration,
+    max_rows: usize,
+}
+
+impl QueryLimits {
+    fn from_env() -> Self {
+        let timeout_ms: u64 = std::env::var("QUERY_TIMEOUT_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(5_000);
+        let max_rows: usize = std::env::var("QUERY_MAX_ROWS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(10_000);
+        Self {
+            timeout: Duration::from_millis(timeout_ms),
+            max_rows,
+        }
+    }
+}
+
+enum QueryError {
+    Sqlite(rusqlite::Error),
+    TimedOut,
+    TooManyRows(usize),
+}
+
+impl QueryError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            QueryError::TimedOut => actix_web::http::StatusCode::REQUEST_TIMEOUT,
+            QueryError::Sqlite(_) | QueryError::TooManyRows(_) => {
+                actix_web::http::StatusCode::BAD_REQUEST
+            }
+        }
+    }
+}
+
+impl From<rusqlite::Error> for QueryError {
+    fn from(e: rusqlite::Error) -> Self {
+        if e.sqlite_error_code() == Some(rusqlite::ErrorCode::OperationInterrupted) {
+            QueryError::TimedOut
+        } else {
+            QueryError::Sqlite(e)
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Sqlite(e) => e.fmt(f),
+            QueryError::TimedOut => write!(f, "query exceeded its deadline"),
+            QueryError::TooManyRows(max) => write!(f, "query returned more than {} rows", max),
+        }
+    }
+}
+
+impl fmt::Debug for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
     }
 }
 
 async fn perform_query<P: Params>(
-    fetcher: Arc<Mutex<DataFetcher>>,
+    query_sem: Arc<Semaphore>,
+    limits: QueryLimits,
+    cache: Arc<QueryCache>,
     sql: String,
+    cache_key: String,
     params: P,
 ) -> Result<web::Json<serde_json::Value>, Error> {
-    fetcher
-        .lock()
-        .await
-        .refresh()
-        .await
-        .map_err(WrappedError::internal)?;
+    if let Some(cached) = cache.get(&cache_key) {
+        return Ok(web::Json((*cached).clone()));
+    }
+    // Captured before the read so a refresh landing mid-query is detected:
+    // see `QueryCache::insert_if_current`.
+    let generation = cache.generation();
 
-    let conn = rusqlite::Connection::open_with_flags("db.sqlite", OpenFlags::SQLITE_OPEN_READ_ONLY)
-        .map_err(WrappedError::internal)?;
-    let mut stmt = conn.prepare(&sql).map_err(WrappedError::user)?;
-    let cols = stmt
-        .column_names()
-        .iter()
-        .map(|it| it.to_string())
-        .collect::<Vec<_>>();
-    let ret = stmt
-        .query_map(params, |row| {
-            let mut row_json = serde_json::Map::new();
-            for (i, name) in (0..cols.len()).zip(cols.iter()) {
-                let elt: DbValue = row.get(i)?;
-                row_json.extend([(name.to_string(), elt.0)]);
+    let _permit = query_sem.try_acquire().map_err(|_| {
+        WrappedError::unavailable("too many concurrent queries, try again shortly")
+    })?;
+
+    let result = with_read_conn(|conn| -> Result<serde_json::Value, QueryError> {
+        let deadline = Instant::now() + limits.timeout;
+        conn.progress_handler(1000, Some(move || Instant::now() >= deadline));
+
+        let result = (|| {
+            let mut stmt = conn.prepare(&sql)?;
+            let cols = stmt
+                .column_names()
+                .iter()
+                .map(|it| it.to_string())
+                .collect::<Vec<_>>();
+            let rows = stmt.query_map(params, |row| {
+                let mut row_json = serde_json::Map::new();
+                for (i, name) in (0..cols.len()).zip(cols.iter()) {
+                    let elt: DbValue = row.get(i)?;
+                    row_json.extend([(name.to_string(), elt.0)]);
+                }
+                Ok(serde_json::Value::Object(row_json))
+            })?;
+
+            let mut ret = Vec::new();
+            for row in rows {
+                ret.push(row?);
+                if ret.len() > limits.max_rows {
+                    return Err(QueryError::TooManyRows(limits.max_rows));
+                }
             }
-            Ok(serde_json::Value::Object(row_json))
-        })
-        .map_err(WrappedError::user)?
-        .collect::<Result<_, _>>()
-        .map_err(WrappedError::user)?;
+            Ok(serde_json::Value::Array(ret))
+        })();
 
-    Ok(web::Json(serde_json::Value::Array(ret)))
+        conn.progress_handler(0, None::<fn() -> bool>);
+        result
+    })
+    .map_err(|e| {
+        let status = e.status_code();
+        Error::from(WrappedError(e, status))
+    })?;
+
+    cache.insert_if_current(cache_key, Arc::new(result.clone()), generation);
+
+    Ok(web::Json(result))
 }
 
 struct DataFetcher {
     conn: rusqlite::Connection,
     client: reqwest::Client,
     api_token: String,
-    last_ts: Instant,
+    cache: Arc<QueryCache>,
 }
 
 const TIME_TO_REFRESH: Duration = Duration::from_secs(300);
 
-impl DataFetcher {
-    async fn refresh(&mut self) -> anyhow::Result<()> {
-        let delta = Instant::now() - self.last_ts;
-        if delta > TIME_TO_REFRESH {
-            self.fetch_and_load_data().await?;
-        }
-        Ok(())
-    }
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
 
+impl DataFetcher {
     async fn fetch_and_load_data(&mut self) -> anyhow::Result<()> {
         #[derive(serde::Deserialize)]
         struct VersionsEntry {
@@ -156,7 +250,6 @@ impl DataFetcher {
             .await?
             .json::<Vec<VersionTypeEntry>>()
             .await?;
-        self.last_ts = Instant::now();
 
         let tx = self.conn.transaction()?;
         {
@@ -176,15 +269,23 @@ impl DataFetcher {
         tx.commit()?;
         println!("Inserted into database");
 
+        // Results cached from before this refresh may reflect rows that
+        // no longer exist, so they must not outlive the commit above.
+        self.cache.clear();
+
         Ok(())
     }
 }
 
-async fn update_db() -> anyhow::Result<DataFetcher> {
+async fn update_db(cache: Arc<QueryCache>, metrics: Arc<Metrics>) -> anyhow::Result<DataFetcher> {
     let conn = rusqlite::Connection::open_with_flags(
         "db.sqlite",
         OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE,
     )?;
+    // Matches the read connections' busy_timeout (see `db.rs`): without it
+    // the writer can itself get `SQLITE_BUSY` trying to upgrade to an
+    // exclusive lock while a reader's SHARED lock is still held.
+    conn.busy_timeout(db::BUSY_TIMEOUT)?;
     conn.execute(
         "CREATE TABLE IF NOT EXISTS versions (\
          id INT PRIMARY KEY,\
@@ -211,14 +312,34 @@ async fn update_db() -> anyhow::Result<DataFetcher> {
             conn,
             client,
             api_token,
-            last_ts: Instant::now(),
+            cache,
         }
     };
-    fetcher.fetch_and_load_data().await?;
+    let result = fetcher.fetch_and_load_data().await;
+    metrics.record_fetch(result.is_ok());
+    result?;
 
     Ok(fetcher)
 }
 
+/// Runs the periodic CurseForge fetch on its own, away from the request
+/// path: request handlers only ever read through [`with_read_conn`], so
+/// none of them block on network I/O, and a fetch failure just leaves
+/// `last_refresh` stale instead of failing in-flight requests.
+fn spawn_refresh_loop(mut fetcher: DataFetcher, last_refresh: Arc<AtomicU64>, metrics: Arc<Metrics>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(TIME_TO_REFRESH).await;
+            let result = fetcher.fetch_and_load_data().await;
+            metrics.record_fetch(result.is_ok());
+            match result {
+                Ok(()) => last_refresh.store(unix_now(), Ordering::Relaxed),
+                Err(e) => eprintln!("Background data refresh failed: {e:#}"),
+            }
+        }
+    });
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let addr = "127.0.0.1";
@@ -228,9 +349,26 @@ async fn main() -> anyhow::Result<()> {
         .unwrap_or(Ok(8080))?;
     println!("Running on: http://{}:{}", addr, port);
 
-    let fetcher = Arc::new(Mutex::new(update_db().await?));
+    let metrics = Arc::new(Metrics::new());
+    let query_cache = Arc::new(QueryCache::from_env());
+    let fetcher = update_db(query_cache.clone(), metrics.clone()).await?;
+    let last_refresh = Arc::new(AtomicU64::new(unix_now()));
+    spawn_refresh_loop(fetcher, last_refresh.clone(), metrics.clone());
+
+    let query_sem = Arc::new(Semaphore::new(MAX_CONCURRENT_QUERIES));
+    let query_limits = QueryLimits::from_env();
+    // Built once and cloned into each worker below so every worker shares
+    // the same bucket map; constructing it inside the factory closure
+    // would give each worker its own map and multiply the effective limit
+    // by the worker count.
+    let rate_limiter = RateLimiter::new(RateLimiterConfig::from_env());
+    let graphql_schema = graphql::build_schema();
     HttpServer::new(move || {
-        let fetcher = fetcher.clone();
+        let query_sem = query_sem.clone();
+        let query_cache = query_cache.clone();
+        let rate_limiter = rate_limiter.clone();
+        let graphql_schema = graphql_schema.clone();
+        let metrics = metrics.clone();
         async fn default_handler(req: ServiceRequest) -> Result<ServiceResponse, Error> {
             let (req, _) = req.into_parts();
             let file = NamedFile::open_async("./static/404.html").await?;
@@ -239,10 +377,26 @@ async fn main() -> anyhow::Result<()> {
             Ok(ServiceResponse::new(req, res))
         }
         App::new()
-            .route(
-                "/query.json",
-                web::post().to(move |sql| query(fetcher.clone(), sql)),
+            .app_data(web::Data::new(last_refresh.clone()))
+            .app_data(web::Data::new(metrics.clone()))
+            .service(
+                web::resource("/query.json")
+                    .wrap(rate_limiter)
+                    .route(web::post().to(move |sql| {
+                        query(
+                            query_sem.clone(),
+                            query_limits,
+                            query_cache.clone(),
+                            metrics.clone(),
+                            sql,
+                        )
+                    })),
             )
+            .route("/health", web::get().to(health))
+            .route("/metrics", web::get().to(metrics_endpoint))
+            .app_data(web::Data::new(graphql_schema))
+            .route("/graphql", web::post().to(graphql::graphql_handler))
+            .route("/graphql/playground", web::get().to(graphql::graphql_playground))
             .service(web::redirect("/", "/static/index.html"))
             .service(
                 actix_files::Files::new("/static", "./static")
@@ -258,9 +412,175 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn query(
-    fetcher: Arc<Mutex<DataFetcher>>,
+/// Body accepted by `/query.json`. Either a bare SQL string (the original
+/// behavior), or a JSON object naming the SQL text and its bound params
+/// so callers don't have to inline literals into the query.
+#[derive(serde::Deserialize)]
+struct QueryBody {
     sql: String,
+    #[serde(default)]
+    params: Option<QueryParamsJson>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum QueryParamsJson {
+    Positional(Vec<serde_json::Value>),
+    Named(std::collections::HashMap<String, serde_json::Value>),
+}
+
+enum BoundParams {
+    None,
+    Positional(Vec<rusqlite::types::Value>),
+    Named(Vec<(String, rusqlite::types::Value)>),
+}
+
+fn json_to_sql_value(v: &serde_json::Value) -> rusqlite::types::Value {
+    match v {
+        serde_json::Value::Null => rusqlite::types::Value::Null,
+        serde_json::Value::Bool(b) => rusqlite::types::Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => n
+            .as_i64()
+            .map(rusqlite::types::Value::Integer)
+            .unwrap_or_else(|| rusqlite::types::Value::Real(n.as_f64().unwrap_or(0.0))),
+        serde_json::Value::String(s) => rusqlite::types::Value::Text(s.clone()),
+        other => rusqlite::types::Value::Text(other.to_string()),
+    }
+}
+
+/// Accepts keys with or without the leading sigil rusqlite expects for
+/// named params, so both `{"name": ...}` and `{":name": ...}` bind to
+/// `:name` in the SQL text.
+fn normalize_named_key(key: String) -> String {
+    if key.starts_with([':', '@', '$']) {
+        key
+    } else {
+        format!(":{key}")
+    }
+}
+
+fn parse_query_body(body: &[u8]) -> Result<(String, BoundParams), std::str::Utf8Error> {
+    let text = std::str::from_utf8(body)?;
+
+    if let Ok(parsed) = serde_json::from_str::<QueryBody>(text) {
+        let params = match parsed.params {
+            None => BoundParams::None,
+            Some(QueryParamsJson::Positional(vals)) => {
+                BoundParams::Positional(vals.iter().map(json_to_sql_value).collect())
+            }
+            Some(QueryParamsJson::Named(map)) => BoundParams::Named(
+                map.into_iter()
+                    .map(|(k, v)| (normalize_named_key(k), json_to_sql_value(&v)))
+                    .collect(),
+            ),
+        };
+        return Ok((parsed.sql, params));
+    }
+
+    Ok((text.to_string(), BoundParams::None))
+}
+
+/// Cache key distinguishing calls with the same SQL text but different
+/// bound params: keying on `sql` alone would serve one caller's params to
+/// another (`WHERE id=?1` with `params:[1]` must not answer `params:[2]`).
+/// Named params are sorted first so key order doesn't affect the key.
+fn cache_key_for(sql: &str, params: &BoundParams) -> String {
+    match params {
+        BoundParams::None => sql.to_string(),
+        BoundParams::Positional(vals) => format!("{sql}\0{vals:?}"),
+        BoundParams::Named(vals) => {
+            let mut sorted: Vec<&(String, rusqlite::types::Value)> = vals.iter().collect();
+            sorted.sort_by(|a, b| a.0.cmp(&b.0));
+            format!("{sql}\0{sorted:?}")
+        }
+    }
+}
+
+async fn query(
+    query_sem: Arc<Semaphore>,
+    query_limits: QueryLimits,
+    query_cache: Arc<QueryCache>,
+    metrics: Arc<Metrics>,
+    body: web::Bytes,
 ) -> Result<web::Json<serde_json::Value>, Error> {
-    perform_query(fetcher, sql, []).await
+    let start = Instant::now();
+
+    let (sql, params) = match parse_query_body(&body) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            metrics.record_query(start.elapsed(), actix_web::http::StatusCode::BAD_REQUEST);
+            return Err(WrappedError::user(e).into());
+        }
+    };
+    let cache_key = cache_key_for(&sql, &params);
+    let result = match params {
+        BoundParams::None => {
+            perform_query(query_sem, query_limits, query_cache, sql, cache_key, []).await
+        }
+        BoundParams::Positional(vals) => {
+            perform_query(
+                query_sem,
+                query_limits,
+                query_cache,
+                sql,
+                cache_key,
+                rusqlite::params_from_iter(vals),
+            )
+            .await
+        }
+        BoundParams::Named(vals) => {
+            let refs: Vec<(&str, &dyn rusqlite::ToSql)> =
+                vals.iter().map(|(k, v)| (k.as_str(), v as &dyn rusqlite::ToSql)).collect();
+            perform_query(
+                query_sem,
+                query_limits,
+                query_cache,
+                sql,
+                cache_key,
+                refs.as_slice(),
+            )
+            .await
+        }
+    };
+
+    let status = match &result {
+        Ok(_) => actix_web::http::StatusCode::OK,
+        Err(e) => e.as_response_error().status_code(),
+    };
+    metrics.record_query(start.elapsed(), status);
+
+    result
+}
+
+async fn health(
+    last_refresh: web::Data<Arc<AtomicU64>>,
+) -> Result<web::Json<serde_json::Value>, Error> {
+    let last_refresh_unix = last_refresh.load(Ordering::Relaxed);
+    let seconds_since_refresh = unix_now().saturating_sub(last_refresh_unix);
+
+    let (versions_count, version_types_count) =
+        with_read_conn(|conn| -> rusqlite::Result<(i64, i64)> {
+            let versions = conn.query_row("SELECT COUNT(*) FROM versions", [], |r| r.get(0))?;
+            let version_types =
+                conn.query_row("SELECT COUNT(*) FROM versionTypes", [], |r| r.get(0))?;
+            Ok((versions, version_types))
+        })
+        .map_err(WrappedError::internal)?;
+
+    Ok(web::Json(serde_json::json!({
+        "last_refresh_unix": last_refresh_unix,
+        "seconds_since_refresh": seconds_since_refresh,
+        "versions_count": versions_count,
+        "version_types_count": version_types_count,
+    })))
+}
+
+async fn metrics_endpoint(
+    metrics: web::Data<Arc<Metrics>>,
+    last_refresh: web::Data<Arc<AtomicU64>>,
+) -> HttpResponse {
+    let seconds_since_refresh = unix_now().saturating_sub(last_refresh.load(Ordering::Relaxed));
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render(seconds_since_refresh))
 }
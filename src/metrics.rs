@@ -0,0 +1,166 @@
+//! In-process counters and a latency histogram, rendered in Prometheus
+//! text exposition format. Hand-rolled rather than pulling in the
+//! `prometheus` crate, since this is a single small process with a
+//! handful of series.
+
+use std::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use actix_web::http::StatusCode;
+
+/// Upper bounds (seconds) for the query latency histogram, mirroring the
+/// Prometheus client libraries' own default bucket boundaries.
+const LATENCY_BUCKETS_SECS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub struct Metrics {
+    queries_total: AtomicU64,
+    query_errors_4xx: AtomicU64,
+    query_errors_5xx: AtomicU64,
+    latency_buckets: Vec<AtomicU64>,
+    latency_sum_micros: AtomicU64,
+    fetch_successes: AtomicU64,
+    fetch_failures: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            queries_total: AtomicU64::new(0),
+            query_errors_4xx: AtomicU64::new(0),
+            query_errors_5xx: AtomicU64::new(0),
+            latency_buckets: LATENCY_BUCKETS_SECS.iter().map(|_| AtomicU64::new(0)).collect(),
+            latency_sum_micros: AtomicU64::new(0),
+            fetch_successes: AtomicU64::new(0),
+            fetch_failures: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_query(&self, elapsed: Duration, status: StatusCode) {
+        self.queries_total.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+
+        if status.is_client_error() {
+            self.query_errors_4xx.fetch_add(1, Ordering::Relaxed);
+        } else if status.is_server_error() {
+            self.query_errors_5xx.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let elapsed_secs = elapsed.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS_SECS.iter().zip(self.latency_buckets.iter()) {
+            if elapsed_secs <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn record_fetch(&self, success: bool) {
+        let counter = if success {
+            &self.fetch_successes
+        } else {
+            &self.fetch_failures
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders all series as Prometheus text exposition format.
+    /// `seconds_since_refresh` is passed in rather than stored here since
+    /// it's derived from the shared `last_refresh` timestamp in `main.rs`.
+    pub fn render(&self, seconds_since_refresh: u64) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "# HELP cursemap_queries_total Total /query.json requests handled.").unwrap();
+        writeln!(out, "# TYPE cursemap_queries_total counter").unwrap();
+        writeln!(
+            out,
+            "cursemap_queries_total {}",
+            self.queries_total.load(Ordering::Relaxed)
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP cursemap_query_errors_total /query.json requests that errored, by status class."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cursemap_query_errors_total counter").unwrap();
+        writeln!(
+            out,
+            "cursemap_query_errors_total{{class=\"4xx\"}} {}",
+            self.query_errors_4xx.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "cursemap_query_errors_total{{class=\"5xx\"}} {}",
+            self.query_errors_5xx.load(Ordering::Relaxed)
+        )
+        .unwrap();
+
+        writeln!(out, "# HELP cursemap_query_duration_seconds /query.json latency.").unwrap();
+        writeln!(out, "# TYPE cursemap_query_duration_seconds histogram").unwrap();
+        for (bound, bucket) in LATENCY_BUCKETS_SECS.iter().zip(self.latency_buckets.iter()) {
+            writeln!(
+                out,
+                "cursemap_query_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                bucket.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+        let queries_total = self.queries_total.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "cursemap_query_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            queries_total
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "cursemap_query_duration_seconds_sum {}",
+            self.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        )
+        .unwrap();
+        writeln!(out, "cursemap_query_duration_seconds_count {}", queries_total).unwrap();
+
+        writeln!(
+            out,
+            "# HELP cursemap_fetch_total Upstream CurseForge fetch attempts, by outcome."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cursemap_fetch_total counter").unwrap();
+        writeln!(
+            out,
+            "cursemap_fetch_total{{outcome=\"success\"}} {}",
+            self.fetch_successes.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "cursemap_fetch_total{{outcome=\"failure\"}} {}",
+            self.fetch_failures.load(Ordering::Relaxed)
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP cursemap_seconds_since_refresh Seconds since the last successful data refresh."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cursemap_seconds_since_refresh gauge").unwrap();
+        writeln!(out, "cursemap_seconds_since_refresh {}", seconds_since_refresh).unwrap();
+
+        out
+    }
+}
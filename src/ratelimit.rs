@@ -0,0 +1,168 @@
+//! Token-bucket rate limiting middleware, keyed by client IP.
+//!
+//! Kept deliberately simple: an in-memory `HashMap` guarded by a `Mutex`,
+//! similar to the memory-backed limiters used by other open-source
+//! Minecraft-adjacent API servers. Good enough for a single-process
+//! deployment; a multi-instance deployment would need a shared store
+//! instead.
+
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+
+/// How long an idle bucket is kept around before being evicted, so that a
+/// stream of one-off clients doesn't grow the map forever.
+const BUCKET_TTL: Duration = Duration::from_secs(600);
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Clone, Copy)]
+pub struct RateLimiterConfig {
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+}
+
+impl RateLimiterConfig {
+    pub fn from_env() -> Self {
+        let capacity = std::env::var("RATE_LIMIT_CAPACITY")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(20.0);
+        let refill_per_sec = std::env::var("RATE_LIMIT_REFILL_PER_SEC")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1.0);
+        Self {
+            capacity,
+            refill_per_sec,
+        }
+    }
+}
+
+enum Decision {
+    Allow,
+    Deny { retry_after_secs: u64 },
+}
+
+/// Cheap to clone: `buckets` is an `Arc`, so every clone shares the same
+/// map. Build one `RateLimiter` in `main` and clone it into each worker's
+/// `App` rather than calling `new` per worker, or each worker ends up with
+/// its own bucket map and the effective limit is multiplied by the worker
+/// count.
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: RateLimiterConfig,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimiterConfig) -> Self {
+        Self {
+            config,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn check(&self, ip: IpAddr) -> Decision {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("rate limiter mutex poisoned");
+
+        buckets.retain(|_, bucket| now.duration_since(bucket.last_refill) < BUCKET_TTL);
+
+        let bucket = buckets.entry(ip).or_insert_with(|| Bucket {
+            tokens: self.config.capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.config.refill_per_sec).min(self.config.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Decision::Allow
+        } else {
+            let deficit = 1.0 - bucket.tokens;
+            let retry_after_secs = (deficit / self.config.refill_per_sec).ceil().max(1.0) as u64;
+            Decision::Deny { retry_after_secs }
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service,
+            config: self.config,
+            buckets: self.buckets.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: S,
+    config: RateLimiterConfig,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let limiter = RateLimiter {
+            config: self.config,
+            buckets: self.buckets.clone(),
+        };
+        let decision = req.peer_addr().map(|addr| limiter.check(addr.ip()));
+
+        match decision {
+            Some(Decision::Deny { retry_after_secs }) => {
+                let response = HttpResponse::TooManyRequests()
+                    .insert_header(("Retry-After", retry_after_secs.to_string()))
+                    .finish();
+                let (req, _) = req.into_parts();
+                Box::pin(async move { Ok(ServiceResponse::new(req, response).map_into_right_body()) })
+            }
+            // No peer address (e.g. in tests) fails open rather than
+            // blocking requests we have no key to bucket.
+            Some(Decision::Allow) | None => {
+                let fut = self.service.call(req);
+                Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) })
+            }
+        }
+    }
+}